@@ -5,25 +5,65 @@ mod tap;
 use core::fmt::{self, Display, Formatter};
 use std::{
     io::{Error, ErrorKind},
-    thread::sleep,
+    thread::{self, sleep},
     time::{Duration, Instant},
 };
 
 use clap::Parser;
 use colored::{ColoredString, Colorize};
-use serialport::{ClearBuffer, SerialPort};
-use tap::Tap;
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+use tap::{Diagnostic, Tap};
 
 #[derive(Parser)]
 #[command(version, author, about)]
 struct Args {
     /// Path to the first serial port
     pub first: String,
-    /// Path to the second serial port
-    pub second: String,
+    /// Path to the second serial port; omit to loop the first port back to itself
+    /// (TX↔RX, RTS↔CTS, DTR↔DSR wired together on a single adapter)
+    pub second: Option<String>,
 }
 
 const BAUD_RATES: [u32; 4] = [9_600, 19_200, 38_400, 115_200];
+const DATA_BITS: [DataBits; 4] = [
+    DataBits::Five,
+    DataBits::Six,
+    DataBits::Seven,
+    DataBits::Eight,
+];
+const PARITIES: [Parity; 3] = [Parity::None, Parity::Odd, Parity::Even];
+const STOP_BITS: [StopBits; 2] = [StopBits::One, StopBits::Two];
+
+/// Mask applied to each byte of the test pattern: frames narrower than 8 data bits never
+/// transmit their high bits, so the read-back comparison must not expect them either.
+fn data_mask(data_bits: DataBits) -> u8 {
+    let bits = match data_bits {
+        DataBits::Five => 5,
+        DataBits::Six => 6,
+        DataBits::Seven => 7,
+        DataBits::Eight => 8,
+    };
+    ((1u16 << bits) - 1) as u8
+}
+
+fn format_name(data_bits: DataBits, parity: Parity, stop_bits: StopBits) -> String {
+    let bits = match data_bits {
+        DataBits::Five => '5',
+        DataBits::Six => '6',
+        DataBits::Seven => '7',
+        DataBits::Eight => '8',
+    };
+    let parity = match parity {
+        Parity::None => 'N',
+        Parity::Odd => 'O',
+        Parity::Even => 'E',
+    };
+    let stop_bits = match stop_bits {
+        StopBits::One => '1',
+        StopBits::Two => '2',
+    };
+    format!("{bits}{parity}{stop_bits}")
+}
 
 fn wait<P, E>(mut predicate: P, timeout: Duration) -> Result<bool, E>
 where
@@ -79,10 +119,11 @@ impl Display for Pins {
     }
 }
 
-fn test_transmit<S: SerialPort>(
+fn test_transmit(
+    mask: u8,
     pins: &Pins,
-    first: &mut S,
-    second: &mut S,
+    first: &mut dyn SerialPort,
+    second: &mut dyn SerialPort,
 ) -> Result<(), serialport::Error> {
     // Define the pins
     first.write_data_terminal_ready(pins.data_terminal_ready)?;
@@ -101,8 +142,8 @@ fn test_transmit<S: SerialPort>(
         Duration::from_millis(100),
     )?;
 
-    // Send a pattern
-    let pattern: Vec<_> = (u8::MIN..=u8::MAX).collect();
+    // Send a pattern, masked to the bits the current frame format actually carries
+    let pattern: Vec<_> = (u8::MIN..=u8::MAX).map(|byte| byte & mask).collect();
     first.write_all(&pattern)?;
 
     // Wait for the input end to receive at least N bytes
@@ -133,24 +174,258 @@ fn test_transmit<S: SerialPort>(
     }
 }
 
+/// PRBS-7 generator (x^7 + x^6 + 1): a 7-bit shift register seeded non-zero, packing its
+/// output MSB-first into bytes so both ends can regenerate the identical stream without
+/// buffering it.
+struct Prbs7 {
+    state: u8,
+}
+
+impl Prbs7 {
+    fn new() -> Self {
+        Self { state: 0x7f }
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        let mut byte = 0;
+        for _ in 0..8 {
+            let bit = (self.state >> 6 ^ self.state >> 5) & 1;
+            self.state = (self.state << 1 | bit) & 0x7f;
+            byte = byte << 1 | bit;
+        }
+        byte
+    }
+}
+
+const BER_SAMPLE_BYTES: usize = 64 * 1024;
+const BER_CHUNK_BYTES: usize = 4096;
+/// Reject any amount of bit corruption; a clean cable should read back exactly what was sent.
+const BER_THRESHOLD: f64 = 0.0;
+
+enum BerOutcome {
+    Io(serialport::Error),
+    ExcessiveErrors {
+        bit_errors: u64,
+        total_bits: u64,
+        bits_per_second: f64,
+    },
+}
+
+impl From<serialport::Error> for BerOutcome {
+    fn from(err: serialport::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<std::io::Error> for BerOutcome {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err.into())
+    }
+}
+
+impl Diagnostic for BerOutcome {
+    fn diagnostic(self) {
+        match self {
+            Self::Io(err) => err.diagnostic(),
+            Self::ExcessiveErrors {
+                bit_errors,
+                total_bits,
+                bits_per_second,
+            } => {
+                let ber = bit_errors as f64 / total_bits as f64;
+                println!(
+                    "  {}: {ber:.3e} ({bit_errors}/{total_bits} bits)",
+                    "BER".green()
+                );
+                println!("  {}: {bits_per_second:.0} bps", "throughput".green());
+            }
+        }
+    }
+}
+
+/// Stream a PRBS-7 sequence from `tx` to `rx`, regenerating it on the receiving side to
+/// compare without buffering the whole transfer, and report `not ok` if the measured
+/// bit-error-rate exceeds [`BER_THRESHOLD`]. The write runs on its own thread, concurrently
+/// with the read loop below, so the test doesn't depend on the driver's RX buffer being able
+/// to absorb the whole sample unread.
+fn test_ber(tx: &mut dyn SerialPort, rx: &mut dyn SerialPort) -> Result<(), BerOutcome> {
+    let mut writer = tx.try_clone()?;
+    let start = Instant::now();
+
+    // `thread::scope` joins the writer before returning no matter which path out of the
+    // closure below is taken, so a timeout or read error can never leave it running loose
+    // against the port after this function has returned.
+    thread::scope(|scope| {
+        let write = scope.spawn(move || -> std::io::Result<()> {
+            let mut tx_prbs = Prbs7::new();
+            let mut sent = 0;
+            while sent < BER_SAMPLE_BYTES {
+                let chunk: Vec<_> = (0..BER_CHUNK_BYTES.min(BER_SAMPLE_BYTES - sent))
+                    .map(|_| tx_prbs.next_byte())
+                    .collect();
+                writer.write_all(&chunk)?;
+                sent += chunk.len();
+            }
+            Ok(())
+        });
+
+        let read = (|| -> Result<(u64, u64), BerOutcome> {
+            let mut rx_prbs = Prbs7::new();
+            let mut bit_errors = 0u64;
+            let mut received = 0;
+            let mut buf = [0; BER_CHUNK_BYTES];
+            while received < BER_SAMPLE_BYTES {
+                let ready = wait(
+                    || rx.bytes_to_read().map(|n| n > 0),
+                    Duration::from_millis(500),
+                )?;
+                if !ready {
+                    return Err(Error::from(ErrorKind::TimedOut).into());
+                }
+
+                let to_read = BER_CHUNK_BYTES.min(BER_SAMPLE_BYTES - received);
+                rx.read_exact(&mut buf[..to_read])?;
+                for &byte in &buf[..to_read] {
+                    bit_errors += u64::from((byte ^ rx_prbs.next_byte()).count_ones());
+                }
+                received += to_read;
+            }
+            Ok((bit_errors, (BER_SAMPLE_BYTES * 8) as u64))
+        })();
+
+        write.join().expect("BER writer thread panicked")?;
+        let (bit_errors, total_bits) = read?;
+
+        let bits_per_second = total_bits as f64 / start.elapsed().as_secs_f64();
+        let ber = bit_errors as f64 / total_bits as f64;
+
+        if ber > BER_THRESHOLD {
+            Err(BerOutcome::ExcessiveErrors {
+                bit_errors,
+                total_bits,
+                bits_per_second,
+            })
+        } else {
+            Ok(())
+        }
+    })
+}
+
+/// Bytes written past what the driver's TX buffer can absorb without the peer draining it;
+/// large enough to overrun any realistic kernel or USB-UART FIFO.
+const FLOW_CONTROL_PAYLOAD_BYTES: usize = 1 << 16;
+
+/// Enables hardware flow control on both ends, then proves it actually throttles the sender:
+/// write more than the driver can buffer while `rx` is left undrained, confirm the backlog
+/// queues up with CTS deasserted on the sending side, then resume reading and confirm it
+/// drains once CTS reasserts.
+fn test_flow_control(tx: &mut dyn SerialPort, rx: &mut dyn SerialPort) -> serialport::Result<()> {
+    tx.set_flow_control(FlowControl::Hardware)?;
+    rx.set_flow_control(FlowControl::Hardware)?;
+    tx.clear(ClearBuffer::All)?;
+    rx.clear(ClearBuffer::All)?;
+
+    let payload = vec![0xa5; FLOW_CONTROL_PAYLOAD_BYTES];
+    let mut writer = tx.try_clone()?;
+
+    // Run the whole stall/drain check in a scope so the writer thread is joined, and the
+    // result computed below, before we reset flow control on every exit path -- including
+    // the "sender didn't stall" case, which is the failure mode this test exists to catch and
+    // must not leave both ports stuck in `FlowControl::Hardware` for the rest of the run.
+    let result = thread::scope(|scope| {
+        let write = scope.spawn(move || writer.write_all(&payload));
+
+        let outcome = (|| -> serialport::Result<()> {
+            let stalled = wait(
+                || -> serialport::Result<bool> {
+                    Ok(tx.bytes_to_write()? > 0 && !tx.read_clear_to_send()?)
+                },
+                Duration::from_millis(500),
+            )?;
+            if !stalled {
+                return Err(Error::other("sender did not stall with CTS deasserted").into());
+            }
+
+            let mut sink = vec![0; 4096];
+            let drained = wait(
+                || -> serialport::Result<bool> {
+                    let available = rx.bytes_to_read()? as usize;
+                    if available > 0 {
+                        let size = available.min(sink.len());
+                        rx.read_exact(&mut sink[..size])?;
+                    }
+                    Ok(tx.bytes_to_write()? == 0)
+                },
+                Duration::from_secs(5),
+            )?;
+
+            if drained {
+                Ok(())
+            } else {
+                Err(Error::other("backlog never drained after CTS reasserted").into())
+            }
+        })();
+
+        write
+            .join()
+            .expect("flow control writer thread panicked")?;
+        outcome
+    });
+
+    tx.set_flow_control(FlowControl::None)?;
+    rx.set_flow_control(FlowControl::None)?;
+
+    result
+}
+
 fn main() {
     let args = Args::parse();
 
-    let mut tap = Tap::new(134);
+    let plan = 2
+        + 4
+        + 2
+        + BAUD_RATES.len() * 2
+        + DATA_BITS.len() * PARITIES.len() * STOP_BITS.len() * BAUD_RATES.len() * 0x10 * 2;
+    let mut tap = Tap::new(plan);
 
     let first = serialport::new(&args.first, 9600).open_native();
-    tap.result(format!("open {:?}", args.first), first.as_ref());
     #[cfg(unix)]
-    let first = first.map(posix::FixedTTYPort);
+    let first = first.and_then(posix::FixedTTYPort::new);
+    let first: serialport::Result<Box<dyn SerialPort>> =
+        first.map(|port| Box::new(port) as Box<dyn SerialPort>);
+    tap.result(format!("open {:?}", args.first), first.as_ref());
 
-    let second = serialport::new(&args.second, 9600).open_native();
-    tap.result(format!("open {:?}", args.second), second.as_ref());
-    #[cfg(unix)]
-    let second = second.map(posix::FixedTTYPort);
+    // With no second port given, loop the first one back to itself: `try_clone` hands us an
+    // independent handle to the same device, which is all the pin/transmit tests need.
+    let second: serialport::Result<Box<dyn SerialPort>> = match &args.second {
+        Some(path) => {
+            let second = serialport::new(path, 9600).open_native();
+            #[cfg(unix)]
+            let second = second.and_then(posix::FixedTTYPort::new);
+            second.map(|port| Box::new(port) as Box<dyn SerialPort>)
+        }
+        None => match &first {
+            Ok(port) => port.try_clone(),
+            Err(_) => Err(serialport::Error::new(
+                serialport::ErrorKind::NoDevice,
+                "cannot loop back: the first port failed to open",
+            )),
+        },
+    };
+    let second_description = match &args.second {
+        Some(path) => format!("open {path:?}"),
+        None => format!("open {:?} (looped back to the first port)", args.first),
+    };
+    tap.result(second_description, second.as_ref());
 
     let mut first = first;
     let mut second = second;
 
+    // In single-port loopback mode, `second` is a clone of `first`: RTS and CTS (likewise DTR
+    // and DSR) are the same two wires feeding back on themselves, so testing RTS → CTS and
+    // CTS ← RTS would just retrace the same physical pins twice under different labels.
+    let loopback = args.second.is_none();
+
     if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
         tap.result(
             "test RTS → CTS",
@@ -163,7 +438,9 @@ fn main() {
         tap.skip("test RTS → CTS");
     }
 
-    if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
+    if loopback {
+        tap.skip("test CTS ← RTS (same loopback wiring as RTS → CTS)");
+    } else if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
         tap.result(
             "test CTS ← RTS",
             test_pin(
@@ -187,7 +464,9 @@ fn main() {
         tap.skip("test DTR → DSR");
     }
 
-    if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
+    if loopback {
+        tap.skip("test DSR ← DTR (same loopback wiring as DTR → DSR)");
+    } else if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
         tap.result(
             "test DSR ← DTR",
             test_pin(
@@ -199,6 +478,26 @@ fn main() {
         tap.skip("test DSR ← DTR");
     }
 
+    if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
+        tap.result(
+            "test hardware flow control (1st→2nd)",
+            test_flow_control(first, second),
+        );
+    } else {
+        tap.skip("test hardware flow control (1st→2nd)");
+    }
+
+    if loopback {
+        tap.skip("test hardware flow control (2nd→1st) (same loopback wiring as 1st→2nd)");
+    } else if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
+        tap.result(
+            "test hardware flow control (2nd→1st)",
+            test_flow_control(second, first),
+        );
+    } else {
+        tap.skip("test hardware flow control (2nd→1st)");
+    }
+
     for baud_rate in BAUD_RATES {
         if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
             for port in [first, second] {
@@ -211,25 +510,75 @@ fn main() {
             sleep(Duration::from_millis(10));
         }
 
-        for pins in 0..=0xf {
-            let pins = Pins {
-                data_terminal_ready: pins & 1 != 0,
-                data_set_ready: pins & 2 != 0,
-                request_to_send: pins & 4 != 0,
-                clear_to_send: pins & 8 != 0,
-            };
-            let description = format!("send data at {baud_rate} bps ({pins})");
-            if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
-                tap.result(description, test_transmit(&pins, first, second));
-            } else {
-                tap.skip(description);
-            }
+        let description = format!("measure BER and throughput at {baud_rate} bps (1st→2nd)");
+        if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
+            tap.result(description, test_ber(first, second));
+        } else {
+            tap.skip(description);
+        }
 
-            let description = format!("receive data at {baud_rate} bps ({pins})");
-            if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
-                tap.result(description, test_transmit(&pins, second, first));
-            } else {
-                tap.skip(description);
+        let description = format!("measure BER and throughput at {baud_rate} bps (2nd→1st)");
+        if loopback {
+            tap.skip(format!("{description} (same loopback wiring as 1st→2nd)"));
+        } else if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
+            tap.result(description, test_ber(second, first));
+        } else {
+            tap.skip(description);
+        }
+    }
+
+    for data_bits in DATA_BITS {
+        for parity in PARITIES {
+            for stop_bits in STOP_BITS {
+                let format = format_name(data_bits, parity, stop_bits);
+                let mask = data_mask(data_bits);
+
+                if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
+                    for port in [first, second] {
+                        port.set_data_bits(data_bits)
+                            .expect("failed to set the data bits");
+                        port.set_parity(parity).expect("failed to set the parity");
+                        port.set_stop_bits(stop_bits)
+                            .expect("failed to set the stop bits");
+                    }
+                }
+
+                for baud_rate in BAUD_RATES {
+                    if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
+                        for port in [first, second] {
+                            port.set_baud_rate(baud_rate)
+                                .expect("failed to set the baudrate");
+                            port.clear(ClearBuffer::All)
+                                .expect("failed to clear buffers");
+                        }
+
+                        sleep(Duration::from_millis(10));
+                    }
+
+                    for pins in 0..=0xf {
+                        let pins = Pins {
+                            data_terminal_ready: pins & 1 != 0,
+                            data_set_ready: pins & 2 != 0,
+                            request_to_send: pins & 4 != 0,
+                            clear_to_send: pins & 8 != 0,
+                        };
+                        let description =
+                            format!("send data at {baud_rate} bps, {format} ({pins})");
+                        if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
+                            tap.result(description, test_transmit(mask, &pins, first, second));
+                        } else {
+                            tap.skip(description);
+                        }
+
+                        let description =
+                            format!("receive data at {baud_rate} bps, {format} ({pins})");
+                        if let (Ok(first), Ok(second)) = (&mut first, &mut second) {
+                            tap.result(description, test_transmit(mask, &pins, second, first));
+                        } else {
+                            tap.skip(description);
+                        }
+                    }
+                }
             }
         }
     }
@@ -2,20 +2,30 @@ use std::{
     ffi::c_int,
     io::{Error, Read, Write},
     os::unix::prelude::AsRawFd,
+    sync::Arc,
     time::Duration,
 };
 
 use nix::{
-    ioctl_read_bad,
-    libc::{TIOCM_CD, TIOCM_CTS, TIOCM_DSR, TIOCM_RI, TIOCMGET},
+    ioctl_none_bad, ioctl_read_bad,
+    libc::{TIOCEXCL, TIOCM_CD, TIOCM_CTS, TIOCM_DSR, TIOCM_RI, TIOCMGET, TIOCNXCL},
 };
 use serialport::{ClearBuffer, DataBits, FlowControl, Parity, Result, SerialPort, StopBits};
 
 ioctl_read_bad!(tiocmget, TIOCMGET, c_int);
+ioctl_none_bad!(tiocexcl, TIOCEXCL);
+ioctl_none_bad!(tiocnxcl, TIOCNXCL);
 
-pub struct FixedTTYPort(pub serialport::TTYPort);
+pub struct FixedTTYPort(pub serialport::TTYPort, Arc<()>);
 
 impl FixedTTYPort {
+    /// Wraps an already-open port and requests exclusive access via `TIOCEXCL`, so a second
+    /// tester pointed at the same device fails loudly instead of corrupting this one's reads.
+    pub fn new(port: serialport::TTYPort) -> Result<Self> {
+        unsafe { tiocexcl(port.as_raw_fd()) }.map_err(|err| Error::from(err).into())?;
+        Ok(Self(port, Arc::new(())))
+    }
+
     fn read_pin(&mut self, pin: c_int) -> Result<bool> {
         let mut status = 0;
         unsafe { tiocmget(self.0.as_raw_fd(), &raw mut status) }
@@ -24,6 +34,16 @@ impl FixedTTYPort {
     }
 }
 
+impl Drop for FixedTTYPort {
+    fn drop(&mut self) {
+        // `TIOCEXCL`/`TIOCNXCL` lock the device, not the fd: only the last surviving handle
+        // (this one and its clones share `self.1`) may release it.
+        if Arc::strong_count(&self.1) == 1 {
+            unsafe { tiocnxcl(self.0.as_raw_fd()) }.ok();
+        }
+    }
+}
+
 impl Write for FixedTTYPort {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         self.0.write(buf)
@@ -130,7 +150,11 @@ impl SerialPort for FixedTTYPort {
     }
 
     fn try_clone(&self) -> Result<Box<dyn SerialPort>> {
-        self.0.try_clone()
+        // Keep the clone wrapped too, so its modem-pin reads still go through the TIOCMGET
+        // override above instead of falling back to the plain TTYPort behaviour. Share the
+        // lock handle rather than re-acquiring it, so dropping the clone doesn't unlock the
+        // device out from under the original.
+        Ok(Box::new(Self(self.0.try_clone_native()?, Arc::clone(&self.1))))
     }
 
     fn set_break(&self) -> Result<()> {
@@ -8,8 +8,19 @@ pub trait Diagnostic {
 
 impl Diagnostic for &serialport::Error {
     fn diagnostic(self) {
-        println!("  {}: {:?}", "kind".green(), self.kind);
-        println!("  {}: {}", "description".green(), self.description);
+        if matches!(
+            self.kind,
+            serialport::ErrorKind::Io(std::io::ErrorKind::ResourceBusy)
+        ) {
+            println!("  {}: device busy / exclusively locked", "kind".green());
+            println!(
+                "  {}: another process already has this port open exclusively; close it there first",
+                "hint".green()
+            );
+        } else {
+            println!("  {}: {:?}", "kind".green(), self.kind);
+            println!("  {}: {}", "description".green(), self.description);
+        }
     }
 }
 